@@ -0,0 +1,133 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::{align_of, size_of};
+use core::ptr;
+use core::ptr::NonNull;
+use spin::Mutex;
+use crate::HHDM_OFFSET;
+use crate::memory::PAGE_SIZE;
+use crate::memory::physical_memory::FrameAllocator;
+
+/// Size classes of the per-class free lists. The linked-list allocator spans the whole heap:
+/// it supplies a class's blocks when that class's list is empty, and serves directly any
+/// request whose size or alignment exceeds the biggest class.
+const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// Node of a size class's free list; lives inside the freed block itself.
+struct ListNode {
+    next: Option<&'static mut ListNode>,
+}
+
+pub struct FixedSizeBlockAllocator {
+    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
+    fallback: linked_list_allocator::Heap,
+}
+impl FixedSizeBlockAllocator {
+    pub const fn new() -> Self {
+        const EMPTY: Option<&'static mut ListNode> = None;
+        Self {
+            list_heads: [EMPTY; BLOCK_SIZES.len()],
+            fallback: linked_list_allocator::Heap::empty(),
+        }
+    }
+
+    /// # Safety
+    /// `heap_start..heap_start + heap_size` must be valid, currently-unused, mapped memory.
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        unsafe { self.fallback.init(heap_start as *mut u8, heap_size); }
+    }
+
+    fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
+        match self.fallback.allocate_first_fit(layout) {
+            Ok(ptr) => ptr.as_ptr(),
+            Err(()) => ptr::null_mut(),
+        }
+    }
+
+    /// Index of the smallest size class that can hold `layout`, if any.
+    fn list_index(layout: &Layout) -> Option<usize> {
+        let required = layout.size().max(layout.align());
+        BLOCK_SIZES.iter().position(|&size| size >= required)
+    }
+}
+
+pub struct LockedFixedSizeBlockAllocator {
+    inner: Mutex<FixedSizeBlockAllocator>,
+}
+impl LockedFixedSizeBlockAllocator {
+    pub const fn new() -> Self {
+        Self { inner: Mutex::new(FixedSizeBlockAllocator::new()) }
+    }
+
+    /// # Safety
+    /// See `FixedSizeBlockAllocator::init`.
+    pub unsafe fn init(&self, heap_start: usize, heap_size: usize) {
+        unsafe { self.inner.lock().init(heap_start, heap_size); }
+    }
+}
+
+unsafe impl GlobalAlloc for LockedFixedSizeBlockAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.inner.lock();
+        match FixedSizeBlockAllocator::list_index(&layout) {
+            Some(index) => match allocator.list_heads[index].take() {
+                Some(node) => {
+                    allocator.list_heads[index] = node.next.take();
+                    node as *mut ListNode as *mut u8
+                },
+                None => {
+                    // No free block of this class yet; carve a new one from the fallback region.
+                    let block_size = BLOCK_SIZES[index];
+                    let layout = Layout::from_size_align(block_size, block_size).unwrap();
+                    allocator.fallback_alloc(layout)
+                }
+            },
+            None => allocator.fallback_alloc(layout),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut allocator = self.inner.lock();
+        match FixedSizeBlockAllocator::list_index(&layout) {
+            Some(index) => {
+                debug_assert!(size_of::<ListNode>() <= BLOCK_SIZES[index]);
+                debug_assert!(align_of::<ListNode>() <= BLOCK_SIZES[index]);
+
+                let new_node = ListNode { next: allocator.list_heads[index].take() };
+                let node_ptr = ptr as *mut ListNode;
+                unsafe {
+                    node_ptr.write(new_node);
+                    allocator.list_heads[index] = Some(&mut *node_ptr);
+                }
+            },
+            None => {
+                let ptr = NonNull::new(ptr).expect("dealloc of null pointer");
+                unsafe { allocator.fallback.deallocate(ptr, layout); }
+            }
+        }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: LockedFixedSizeBlockAllocator = LockedFixedSizeBlockAllocator::new();
+
+/// Size of the kernel heap. Frames are pulled from the `FrameAllocator` to back exactly this
+/// much space.
+pub const HEAP_SIZE: usize = 16 * 1024 * 1024;
+
+/// Back the kernel heap with frames from `frame_allocator` and hand them to the global
+/// allocator. The kernel's higher-half direct map already covers all usable physical memory,
+/// so the heap lives at its HHDM-mapped virtual address and needs no page table entries of
+/// its own.
+pub fn init_heap(frame_allocator: &mut impl FrameAllocator) -> Result<(), &'static str> {
+    let frame_count = HEAP_SIZE.div_ceil(PAGE_SIZE);
+    let frame = frame_allocator.allocate_contiguous(frame_count)?;
+    let heap_start = frame.start_address().as_usize() + *HHDM_OFFSET;
+
+    unsafe {
+        ALLOCATOR.init(heap_start, HEAP_SIZE);
+    }
+
+    serial_println!("heap: initialized {} bytes at 0x{:X}", HEAP_SIZE, heap_start);
+
+    Ok(())
+}
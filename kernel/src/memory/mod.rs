@@ -0,0 +1,69 @@
+use core::ops::{Add, AddAssign, Sub};
+use linked_list_allocator::align_up;
+use crate::memory::physical_memory::Frame;
+
+pub mod heap;
+pub mod physical_memory;
+
+pub const PAGE_SIZE: usize = 4096;
+
+/// A physical memory address.
+///
+/// Wrapping it in a newtype keeps frame-index arithmetic (bit offsets, byte offsets) from being
+/// accidentally mixed with raw byte counts or with HHDM-shifted virtual addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PhysicalAddress(usize);
+impl PhysicalAddress {
+    pub const fn new(address: usize) -> Self {
+        Self(address)
+    }
+
+    pub const fn as_usize(self) -> usize {
+        self.0
+    }
+
+    pub fn align_up(self, align: usize) -> Self {
+        Self(align_up(self.0, align))
+    }
+
+    pub fn align_down(self, align: usize) -> Self {
+        Self(self.0 & !(align - 1))
+    }
+
+    pub fn is_aligned(self, align: usize) -> bool {
+        self.0 % align == 0
+    }
+
+    /// Index of the frame containing this address, relative to `base`.
+    pub fn frame_index(self, base: PhysicalAddress) -> usize {
+        (self.0 - base.0) / PAGE_SIZE
+    }
+
+    /// Number of frames needed to cover `[base, self)`, relative to `base` — i.e. the
+    /// exclusive frame index one past the last frame this address partially or fully occupies.
+    /// Use this (rather than `frame_index`) for a range's end bound.
+    pub fn frame_index_ceil(self, base: PhysicalAddress) -> usize {
+        (self.0 - base.0).div_ceil(PAGE_SIZE)
+    }
+
+    pub fn containing_frame(self) -> Frame {
+        Frame::containing_address(self)
+    }
+}
+impl Add<usize> for PhysicalAddress {
+    type Output = Self;
+    fn add(self, rhs: usize) -> Self {
+        Self(self.0 + rhs)
+    }
+}
+impl AddAssign<usize> for PhysicalAddress {
+    fn add_assign(&mut self, rhs: usize) {
+        self.0 += rhs;
+    }
+}
+impl Sub<usize> for PhysicalAddress {
+    type Output = Self;
+    fn sub(self, rhs: usize) -> Self {
+        Self(self.0 - rhs)
+    }
+}
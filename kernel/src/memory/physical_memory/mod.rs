@@ -0,0 +1,45 @@
+mod static_linear_allocator;
+
+pub use static_linear_allocator::StaticLinearAllocator;
+
+use crate::memory::{PAGE_SIZE, PhysicalAddress};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    start_address: PhysicalAddress,
+}
+impl Frame {
+    pub fn containing_address(address: PhysicalAddress) -> Self {
+        Self { start_address: address.align_down(PAGE_SIZE) }
+    }
+
+    pub fn start_address(&self) -> PhysicalAddress {
+        self.start_address
+    }
+
+    pub fn number(&self) -> usize {
+        self.start_address.as_usize() / PAGE_SIZE
+    }
+}
+
+pub trait FrameAllocator {
+    fn allocate_frame(&mut self) -> Result<Frame, &'static str>;
+    fn deallocate_frame(&mut self, frame: Frame) -> Result<(), &'static str>;
+
+    /// Allocate `count` physically contiguous frames.
+    fn allocate_contiguous(&mut self, count: usize) -> Result<Frame, &'static str>;
+    /// Free a run of `count` physically contiguous frames previously returned by `allocate_contiguous`.
+    fn deallocate_contiguous(&mut self, frame: Frame, count: usize) -> Result<(), &'static str>;
+
+    /// Allocate `count` contiguous frames whose starting physical address is a multiple of
+    /// `align` (e.g. 2 MiB for a huge-page mapping, or a device's required DMA boundary).
+    /// `align` must be a power of two, the same assumption `PhysicalAddress::align_down` already
+    /// makes.
+    fn allocate_contiguous_aligned(&mut self, count: usize, align: usize) -> Result<Frame, &'static str>;
+    /// Free a run previously returned by `allocate_contiguous_aligned`. Alignment doesn't affect
+    /// which bits need clearing, so this is just `deallocate_contiguous` under another name —
+    /// implementors can forward to it directly.
+    fn deallocate_contiguous_aligned(&mut self, frame: Frame, count: usize) -> Result<(), &'static str> {
+        self.deallocate_contiguous(frame, count)
+    }
+}
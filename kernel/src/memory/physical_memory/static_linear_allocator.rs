@@ -1,73 +1,305 @@
 use core::mem::size_of;
 use core::ptr;
-use bit::BitIndex;
-use bitfield::Bit;
 use limine::memory_map;
 use limine::memory_map::EntryType;
 use linked_list_allocator::align_up;
 use rlibc::memset;
-use bit;
-use crate::{HHDM_OFFSET, set_bit, test_bit};
+use crate::HHDM_OFFSET;
 use crate::memory::{PAGE_SIZE, PhysicalAddress};
 use crate::memory::physical_memory::{Frame, FrameAllocator};
 
+/// Number of frames tracked by a single bitmap word.
+const WORD_BITS: usize = u64::BITS as usize;
+
 struct PmmModule {
     start_address: PhysicalAddress,
 
-    bitmap_size: usize,
     bitmap_entry_count: usize,
+    word_count: usize,
+    summary_word_count: usize,
     last_free: Option<usize>,
-    bitmap: *mut u8,
+
+    /// Frame bitmap, one bit per frame, packed into `u64` words.
+    bitmap: *mut u64,
+    /// Summary bitmap, one bit per `bitmap` word, set only when that word is `u64::MAX`
+    /// (fully allocated). Lets allocation skip whole exhausted words in one step.
+    summary: *mut u64,
 
     next: Option<*mut PmmModule>,
 }
 unsafe impl Send for PmmModule {}
 unsafe impl Sync for PmmModule {}
 impl PmmModule {
+    /// Bytes of bitmap + summary storage needed to track `frame_count` frames.
+    fn bytes_for(frame_count: usize) -> usize {
+        let word_count = frame_count.div_ceil(WORD_BITS);
+        let summary_word_count = word_count.div_ceil(WORD_BITS);
+        (word_count + summary_word_count) * size_of::<u64>()
+    }
+
     fn init(start_address: PhysicalAddress, size: usize, memory_maps_start: *mut u8) -> Self {
         let frame_count = size.div_ceil(PAGE_SIZE);
+        let word_count = frame_count.div_ceil(WORD_BITS);
+        let summary_word_count = word_count.div_ceil(WORD_BITS);
+
+        let bitmap = memory_maps_start as *mut u64;
+        let summary = unsafe { memory_maps_start.add(word_count * size_of::<u64>()) } as *mut u64;
 
-        let module = Self {
+        let mut module = Self {
             start_address,
 
-            bitmap_size: frame_count.div_ceil(8),
             bitmap_entry_count: frame_count,
-            bitmap: memory_maps_start,
-
+            word_count,
+            summary_word_count,
             last_free: Some(0),
+
+            bitmap,
+            summary,
+
             next: None,
         };
 
         unsafe {
-            memset(module.bitmap, 0, module.bitmap_size);
+            memset(memory_maps_start, 0, (word_count + summary_word_count) * size_of::<u64>());
+        }
+
+        // The last bitmap word may cover more frames than the module actually has; mark the
+        // padding as allocated so it's never handed out and so its word reads as full.
+        for pad in frame_count..word_count * WORD_BITS {
+            module.set_bit(pad, true);
         }
 
         module
     }
 
-    fn allocate_frames(&mut self, count: usize) -> Option<PhysicalAddress> {
-        if let Some(last_free) = self.last_free {
-            let alloc = self.start_address + last_free * PAGE_SIZE;
-            let bit_base = (alloc - self.start_address) / PAGE_SIZE;
-
-            let byte_index = bit_base / 8;
-            let bit_index =  7 - (bit_base % 8);
-            unsafe { *self.bitmap.add(byte_index) }.set_bit(bit_index, true);
-
-            for i in bit_base..self.bitmap_entry_count {
-                let byte_index = i / 8;
-                let bit_index = 7 - (i % 8);
-                if Bit::bit(&unsafe { *self.bitmap.add(byte_index) }, bit_index) {
-                    self.last_free = Some(bit_base + i);
-                    break;
+    fn storage_size(&self) -> usize {
+        (self.word_count + self.summary_word_count) * size_of::<u64>()
+    }
+
+    fn word(&self, index: usize) -> u64 {
+        unsafe { *self.bitmap.add(index) }
+    }
+
+    fn summary_word(&self, index: usize) -> u64 {
+        unsafe { *self.summary.add(index) }
+    }
+
+    fn set_summary_bit(&mut self, word_index: usize, value: bool) {
+        let summary_word_index = word_index / WORD_BITS;
+        let summary_bit = word_index % WORD_BITS;
+
+        let word = unsafe { &mut *self.summary.add(summary_word_index) };
+        if value {
+            *word |= 1 << summary_bit;
+        } else {
+            *word &= !(1 << summary_bit);
+        }
+    }
+
+    fn bit(&self, bit: usize) -> bool {
+        let word_index = bit / WORD_BITS;
+        let bit_index = bit % WORD_BITS;
+        (self.word(word_index) >> bit_index) & 1 != 0
+    }
+
+    fn set_bit(&mut self, bit: usize, value: bool) {
+        let word_index = bit / WORD_BITS;
+        let bit_index = bit % WORD_BITS;
+
+        let word = unsafe { &mut *self.bitmap.add(word_index) };
+        if value {
+            *word |= 1 << bit_index;
+        } else {
+            *word &= !(1 << bit_index);
+        }
+
+        let is_full = *word == u64::MAX;
+        self.set_summary_bit(word_index, is_full);
+    }
+
+    /// Find the first free frame at or after `from`, skipping fully-allocated words in one
+    /// step via the summary bitmap.
+    fn first_free_from(&self, from: usize) -> Option<usize> {
+        let total_bits = self.word_count * WORD_BITS;
+        if from >= total_bits {
+            return None;
+        }
+
+        let mut word_index = from / WORD_BITS;
+        while word_index < self.word_count {
+            let summary_word_index = word_index / WORD_BITS;
+            let summary_bit = word_index % WORD_BITS;
+            let skip = (self.summary_word(summary_word_index) >> summary_bit).trailing_ones() as usize;
+
+            if skip > 0 {
+                word_index += skip;
+                continue;
+            }
+
+            let mut word = self.word(word_index);
+            if word_index == from / WORD_BITS {
+                let first_bit = from % WORD_BITS;
+                if first_bit > 0 {
+                    word |= (1u64 << first_bit) - 1;
                 }
             }
 
-            return Some(alloc);
+            let free_bit = (!word).trailing_zeros() as usize;
+            if free_bit < WORD_BITS {
+                let index = word_index * WORD_BITS + free_bit;
+                return if index < total_bits { Some(index) } else { None };
+            }
+
+            word_index += 1;
         }
 
         None
     }
+
+    /// Find the first allocated frame at or after `from`, or `word_count * WORD_BITS` if no bit
+    /// at or after `from` is set. The padding bits past the module's last frame read as
+    /// allocated, so a free tail of the module ends at `bitmap_entry_count`.
+    fn first_set_from(&self, from: usize) -> usize {
+        let total_bits = self.word_count * WORD_BITS;
+        let mut word_index = from / WORD_BITS;
+
+        while word_index < self.word_count {
+            let mut word = self.word(word_index);
+            if word_index == from / WORD_BITS {
+                let first_bit = from % WORD_BITS;
+                if first_bit > 0 {
+                    word &= !((1u64 << first_bit) - 1);
+                }
+            }
+
+            if word != 0 {
+                return word_index * WORD_BITS + word.trailing_zeros() as usize;
+            }
+
+            word_index += 1;
+        }
+
+        total_bits
+    }
+
+    /// Find the first run of `count` consecutive free bits at or after `last_free`, without
+    /// reserving it.
+    fn find_run(&self, count: usize) -> Option<usize> {
+        let mut candidate = self.first_free_from(self.last_free?)?;
+
+        loop {
+            let run_end = self.first_set_from(candidate);
+            if run_end - candidate >= count {
+                return Some(candidate);
+            }
+
+            candidate = self.first_free_from(run_end)?;
+        }
+    }
+
+    /// Round `value` up to the nearest bit index congruent to `residue` modulo `stride`.
+    fn round_up_to_residue(value: usize, residue: usize, stride: usize) -> usize {
+        if stride <= 1 {
+            return value;
+        }
+
+        let diff = (residue + stride - value % stride) % stride;
+        value + diff
+    }
+
+    /// Like `find_run`, but the run's starting physical address must be a multiple of `align`.
+    ///
+    /// `align` must be a power of two: the stride/residue math below only lands on a true
+    /// multiple of `align` when `align` evenly divides or is evenly divided by `PAGE_SIZE`,
+    /// which is guaranteed for powers of two but not for an arbitrary `align`.
+    fn find_run_aligned(&self, count: usize, align: usize) -> Option<usize> {
+        debug_assert!(align.is_power_of_two(), "find_run_aligned: align must be a power of two");
+        let stride = align.div_ceil(PAGE_SIZE).max(1);
+        let start_frame = self.start_address.as_usize() / PAGE_SIZE;
+        let residue = (stride - start_frame % stride) % stride;
+
+        let total_bits = self.word_count * WORD_BITS;
+        let mut candidate = Self::round_up_to_residue(self.last_free?, residue, stride);
+
+        while candidate < total_bits {
+            if self.bit(candidate) {
+                let next_free = self.first_free_from(candidate + 1)?;
+                candidate = Self::round_up_to_residue(next_free, residue, stride);
+                continue;
+            }
+
+            let run_end = self.first_set_from(candidate);
+            if run_end - candidate >= count {
+                return Some(candidate);
+            }
+
+            candidate = Self::round_up_to_residue(run_end, residue, stride);
+        }
+
+        None
+    }
+
+    /// Move `last_free` to the first free bit at or after `from`, if any remain.
+    fn advance_last_free(&mut self, from: usize) {
+        self.last_free = self.first_free_from(from);
+    }
+
+    fn allocate_frames(&mut self, count: usize) -> Option<PhysicalAddress> {
+        // `find_run` may have skipped over holes too small for `count` on its way to
+        // `run_start`; re-scan from the pre-search cursor, not past the run, so those holes
+        // stay reachable for the next allocation instead of leaking forever.
+        let search_start = self.last_free?;
+        let run_start = self.find_run(count)?;
+
+        for bit in run_start..run_start + count {
+            self.set_bit(bit, true);
+        }
+        self.advance_last_free(search_start);
+
+        Some(self.start_address + run_start * PAGE_SIZE)
+    }
+
+    fn allocate_frames_aligned(&mut self, count: usize, align: usize) -> Option<PhysicalAddress> {
+        // See the comment in `allocate_frames`: re-scan from the pre-search cursor so holes
+        // skipped while hunting for an aligned run aren't lost.
+        let search_start = self.last_free?;
+        let run_start = self.find_run_aligned(count, align)?;
+
+        for bit in run_start..run_start + count {
+            self.set_bit(bit, true);
+        }
+        self.advance_last_free(search_start);
+
+        Some(self.start_address + run_start * PAGE_SIZE)
+    }
+
+    /// Whether the physical address range owned by this module contains `address`.
+    fn contains(&self, address: PhysicalAddress) -> bool {
+        address >= self.start_address && address < self.start_address + self.bitmap_entry_count * PAGE_SIZE
+    }
+
+    fn deallocate_frames(&mut self, address: PhysicalAddress, count: usize) -> Result<(), &'static str> {
+        let bit_base = address.frame_index(self.start_address);
+
+        // Validate the whole range before clearing anything, so a double free partway through
+        // doesn't leave the leading frames cleared while still reporting an error.
+        for bit in bit_base..bit_base + count {
+            if !self.bit(bit) {
+                return Err("pmm: double free detected");
+            }
+        }
+
+        for bit in bit_base..bit_base + count {
+            self.set_bit(bit, false);
+        }
+
+        match self.last_free {
+            Some(last_free) if last_free <= bit_base => {},
+            _ => self.last_free = Some(bit_base),
+        }
+
+        Ok(())
+    }
 }
 
 pub struct StaticLinearAllocator {
@@ -79,8 +311,10 @@ impl StaticLinearAllocator {
         let buffer_size = memory_regions
             .iter()
             .filter(|entry| entry.entry_type == EntryType::USABLE)
-            .fold(0, |acc, entry|
-                acc + size_of::<PmmModule>() * 2 + entry.length.div_ceil(PAGE_SIZE as u64).div_ceil(8) as usize);
+            .fold(0, |acc, entry| {
+                let frame_count = entry.length.div_ceil(PAGE_SIZE as u64) as usize;
+                acc + size_of::<PmmModule>() * 2 + PmmModule::bytes_for(frame_count)
+            });
 
         serial_println!("pmm: allocator requires {} bytes", buffer_size);
 
@@ -119,12 +353,12 @@ impl StaticLinearAllocator {
                     }
                 };
 
-                let module = PmmModule::init(entry.base as PhysicalAddress, entry.length as usize, bitmap_location);
-                let bitmap_size = module.bitmap_size;
+                let module = PmmModule::init(PhysicalAddress::new(entry.base as usize), entry.length as usize, bitmap_location);
+                let storage_size = module.storage_size();
 
                 ptr::write(module_location, module);
 
-                meta_buffer = meta_buffer.add(size_of::<PmmModule>() * 2 + bitmap_size);
+                meta_buffer = meta_buffer.add(size_of::<PmmModule>() * 2 + storage_size);
             }
         });
 
@@ -134,6 +368,13 @@ impl StaticLinearAllocator {
 
         allocator.allocate_self_memory(containing_entry.0, buffer_size);
 
+        // Entries the bootloader handed over but that aren't free to hand out as-is (the
+        // kernel image, its modules, and reclaimable bootloader structures) still overlap
+        // USABLE regions at their edges often enough that they need to be carved out explicitly.
+        memory_regions.iter()
+            .filter(|entry| matches!(entry.entry_type, EntryType::BOOTLOADER_RECLAIMABLE | EntryType::KERNEL_AND_MODULES))
+            .for_each(|entry| allocator.reserve_range(PhysicalAddress::new(entry.base as usize), entry.length as usize));
+
         Ok(allocator)
     }
 
@@ -141,48 +382,115 @@ impl StaticLinearAllocator {
     fn allocate_self_memory(&mut self, containing_region_number: usize, buffer_size: usize) {
         let mut containing_module: &PmmModule = self.root_module;
         for _ in 0..containing_region_number {
-            containing_module = unsafe { &mut *containing_module.next.unwrap() };
+            containing_module = unsafe { &*containing_module.next.unwrap() };
         }
+        let base = containing_module.start_address;
 
-        let frame_count = buffer_size.div_ceil(PAGE_SIZE);
-        let byte_count = frame_count / 8;
-        let bit_count = frame_count % 8;
+        self.reserve_range(base, buffer_size);
+    }
 
-        unsafe {
-            for i in 0..byte_count {
-                ptr::write(containing_module.bitmap.add(i), 0xFF);
+    /// Mark every frame in `[base, base + length)` as allocated so it is never handed out by
+    /// `allocate_frame`/`allocate_contiguous`. `new` uses it for the allocator's own metadata
+    /// and for the kernel image, its modules and bootloader-reclaimable memory; any other
+    /// physical range (the Limine framebuffer, ACPI tables, an MMIO window) is reserved the
+    /// same way. Only frames inside a module are marked; the rest of the range is ignored.
+    pub fn reserve_range(&mut self, base: PhysicalAddress, length: usize) {
+        let end = base + length;
+
+        let mut module = unsafe { &mut *(self.root_module as *mut PmmModule) };
+        loop {
+            let module_end = module.start_address + module.bitmap_entry_count * PAGE_SIZE;
+
+            if base < module_end && end > module.start_address {
+                let range_start = base.max(module.start_address);
+                let range_end = end.min(module_end);
+
+                let first_bit = range_start.frame_index(module.start_address);
+                let last_bit = range_end.frame_index_ceil(module.start_address);
+
+                for bit in first_bit..last_bit {
+                    module.set_bit(bit, true);
+                }
+
+                if module.last_free.is_some_and(|last_free| (first_bit..last_bit).contains(&last_free)) {
+                    module.advance_last_free(last_bit);
+                }
             }
 
-            ptr::write(containing_module.bitmap.add(byte_count), (1 << bit_count) - 1);
+            match module.next {
+                Some(next) => module = unsafe { &mut *next },
+                None => break,
+            }
         }
-
     }
+
 }
 impl FrameAllocator for StaticLinearAllocator {
     fn allocate_frame(&mut self) -> Result<Frame, &'static str> {
+        self.allocate_contiguous(1).map_err(|_| "pmm: could not allocate frame (memory full)")
+    }
+
+    fn deallocate_frame(&mut self, frame: Frame) -> Result<(), &'static str> {
+        self.deallocate_contiguous(frame, 1)
+    }
+
+    fn allocate_contiguous(&mut self, count: usize) -> Result<Frame, &'static str> {
         let mut module = unsafe { &mut *(self.root_module as *mut PmmModule) };
         loop {
-            let alloc = module.allocate_frames(1);
+            let alloc = module.allocate_frames(count);
 
-            // Return the frame if it was found
+            // Return the frame if a run was found
             if let Some(alloc) = alloc {
-                serial_println!("Allocating frame at address {:X}", alloc);
+                serial_println!("Allocating {} contiguous frame(s) at address {:X}", count, alloc.as_usize());
                 let frame = Frame::containing_address(alloc);
                 return Ok(frame);
             }
-            // Try again with the next module if it exists, otherwise fail
+            // Runs can't straddle modules since modules map disjoint physical regions, so
+            // try the next module if it exists, otherwise fail
             else {
                 if let Some(next) = module.next {
                     module = unsafe { &mut *next };
                 }
                 else {
-                    return Err("pmm: could not allocate frame (memory full)");
+                    return Err("pmm: could not allocate contiguous frames (memory full or too fragmented)");
                 }
             }
         }
     }
 
-    fn deallocate_frame(&mut self, frame: Frame) -> Result<(), &'static str> {
-        todo!()
+    fn deallocate_contiguous(&mut self, frame: Frame, count: usize) -> Result<(), &'static str> {
+        let address = frame.start_address();
+
+        let mut module = unsafe { &mut *(self.root_module as *mut PmmModule) };
+        loop {
+            if module.contains(address) {
+                return module.deallocate_frames(address, count);
+            }
+
+            match module.next {
+                Some(next) => module = unsafe { &mut *next },
+                None => return Err("pmm: address does not belong to any module"),
+            }
+        }
     }
-}
\ No newline at end of file
+
+    fn allocate_contiguous_aligned(&mut self, count: usize, align: usize) -> Result<Frame, &'static str> {
+        let mut module = unsafe { &mut *(self.root_module as *mut PmmModule) };
+        loop {
+            let alloc = module.allocate_frames_aligned(count, align);
+
+            if let Some(alloc) = alloc {
+                serial_println!("Allocating {} contiguous frame(s) aligned to {} at address {:X}", count, align, alloc.as_usize());
+                return Ok(Frame::containing_address(alloc));
+            }
+            else {
+                if let Some(next) = module.next {
+                    module = unsafe { &mut *next };
+                }
+                else {
+                    return Err("pmm: could not allocate aligned contiguous frames (memory full or too fragmented)");
+                }
+            }
+        }
+    }
+}